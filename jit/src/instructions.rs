@@ -1,12 +1,18 @@
 use cranelift::prelude::*;
+use cranelift_module::{FuncId, Module};
 use num_traits::cast::ToPrimitive;
 use rustpython_bytecode::bytecode::{
-    BinaryOperator, CodeObject, ComparisonOperator, Constant, Instruction, Label, NameScope,
+    BinaryOperator, CallType, CodeObject, ComparisonOperator, Constant, Instruction, Label,
+    NameScope,
 };
 use std::collections::HashMap;
 
 use super::{JitCompileError, JitSig, JitType};
 
+/// User trap code for a negative shift amount, which CPython rejects with a
+/// `ValueError` rather than letting it wrap mod the bit width.
+const NEGATIVE_SHIFT_TRAP_CODE: u16 = 0;
+
 #[derive(Clone)]
 struct Local {
     var: Variable,
@@ -24,24 +30,41 @@ impl JitValue {
     }
 }
 
+/// A `Block` that's reachable through a bytecode `Label`, together with the
+/// types of the operand stack that were live the first time the block was
+/// created. Every edge into the block must agree with this shape, since it's
+/// encoded as the block's Cranelift parameters.
+struct BlockWithStack {
+    block: Block,
+    stack_types: Vec<JitType>,
+}
+
 pub struct FunctionCompiler<'a, 'b> {
     builder: &'a mut FunctionBuilder<'b>,
+    module: &'a mut dyn Module,
+    known_funcs: &'a HashMap<String, (FuncId, JitSig)>,
     stack: Vec<JitValue>,
+    func_refs: Vec<(FuncId, JitSig, usize)>,
     variables: HashMap<String, Local>,
-    label_to_block: HashMap<Label, Block>,
+    label_to_block: HashMap<Label, BlockWithStack>,
     pub(crate) sig: JitSig,
 }
 
 impl<'a, 'b> FunctionCompiler<'a, 'b> {
     pub fn new(
         builder: &'a mut FunctionBuilder<'b>,
+        module: &'a mut dyn Module,
+        known_funcs: &'a HashMap<String, (FuncId, JitSig)>,
         arg_names: &[String],
         arg_types: &[JitType],
         entry_block: Block,
     ) -> FunctionCompiler<'a, 'b> {
         let mut compiler = FunctionCompiler {
             builder,
+            module,
+            known_funcs,
             stack: Vec::new(),
+            func_refs: Vec::new(),
             variables: HashMap::new(),
             label_to_block: HashMap::new(),
             sig: JitSig {
@@ -80,10 +103,90 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
         }
     }
 
+    fn stack_values(&self) -> Vec<Value> {
+        self.stack.iter().map(|val| val.val).collect()
+    }
+
+    /// Get the `Block` that a `Label` maps to, creating it - along with one
+    /// block parameter per value currently on the operand stack - the first
+    /// time it's reached. Every subsequent edge into the block must carry a
+    /// stack of the same shape, since the stack is threaded through as block
+    /// parameters rather than compiler-local state.
+    fn get_or_make_block(&mut self, label: Label) -> Result<Block, JitCompileError> {
+        let stack_types: Vec<JitType> = self.stack.iter().map(|val| val.ty.clone()).collect();
+        let builder = &mut self.builder;
+        match self.label_to_block.get(&label) {
+            Some(block_with_stack) => {
+                if block_with_stack.stack_types != stack_types {
+                    return Err(JitCompileError::NotSupported);
+                }
+                Ok(block_with_stack.block)
+            }
+            None => {
+                let block = builder.create_block();
+                for ty in &stack_types {
+                    builder.append_block_param(block, ty.to_cranelift());
+                }
+                self.label_to_block
+                    .insert(label, BlockWithStack { block, stack_types });
+                Ok(block)
+            }
+        }
+    }
+
+    /// Switch to the block that `label` maps to, repopulating the operand stack from its block
+    /// parameters so it reflects whatever the jumping edge handed over.
+    fn switch_to_block_with_stack(&mut self, label: Label) {
+        let block_with_stack = &self.label_to_block[&label];
+        let block = block_with_stack.block;
+        let stack_types = block_with_stack.stack_types.clone();
+        self.builder.switch_to_block(block);
+        self.stack = self
+            .builder
+            .block_params(block)
+            .iter()
+            .zip(stack_types)
+            .map(|(&val, ty)| JitValue { val, ty })
+            .collect();
+    }
+
+    /// Python freely mixes `int` and `float` in arithmetic and comparisons; when
+    /// exactly one of `a`/`b` is an `Int` next to a `Float`, widen it so the rest
+    /// of the operator can be implemented against a single `(Float, Float)` case.
+    fn coerce_numeric(&mut self, a: JitValue, b: JitValue) -> (JitValue, JitValue) {
+        match (&a.ty, &b.ty) {
+            (JitType::Int, JitType::Float) => {
+                let val = self.builder.ins().fcvt_from_sint(types::F64, a.val);
+                (
+                    JitValue {
+                        val,
+                        ty: JitType::Float,
+                    },
+                    b,
+                )
+            }
+            (JitType::Float, JitType::Int) => {
+                let val = self.builder.ins().fcvt_from_sint(types::F64, b.val);
+                (
+                    a,
+                    JitValue {
+                        val,
+                        ty: JitType::Float,
+                    },
+                )
+            }
+            _ => (a, b),
+        }
+    }
+
     fn boolean_val(&mut self, val: JitValue) -> Result<Value, JitCompileError> {
         match val.ty {
             JitType::Float => Err(JitCompileError::NotSupported),
-            JitType::Int => Ok(val.val),
+            JitType::Int => {
+                let zero = self.builder.ins().iconst(types::I64, 0);
+                Ok(self.builder.ins().icmp(IntCC::NotEqual, val.val, zero))
+            }
+            JitType::Bool => Ok(val.val),
         }
     }
 
@@ -93,19 +196,19 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
 
         for (offset, instruction) in bytecode.instructions.iter().enumerate() {
             if let Some(&label) = offset_to_label.get(&offset) {
-                let builder = &mut self.builder;
-                let block = self
-                    .label_to_block
-                    .entry(*label)
-                    .or_insert_with(|| builder.create_block());
-
                 // If the current block is not terminated/filled just jump
-                // into the new block.
+                // into the new block, handing off the operand stack as
+                // block arguments. A filled block's leftover stack is not an
+                // edge into the label, so it is not checked against it.
                 if !self.builder.is_filled() {
-                    self.builder.ins().jump(*block, &[]);
+                    let block = self.get_or_make_block(*label)?;
+                    let args = self.stack_values();
+                    self.builder.ins().jump(block, &args);
+                } else if !self.label_to_block.contains_key(label) {
+                    self.get_or_make_block(*label)?;
                 }
 
-                self.builder.switch_to_block(*block);
+                self.switch_to_block_with_stack(*label);
             }
 
             // Sometimes the bytecode contains instructions after a return
@@ -124,12 +227,11 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
         match instruction {
             Instruction::JumpIfFalse { target } => {
                 let cond = self.stack.pop().ok_or(JitCompileError::BadBytecode)?;
-
-                let then_block = self.builder.create_block();
-                self.label_to_block.insert(*target, then_block);
-
                 let val = self.boolean_val(cond)?;
-                self.builder.ins().brz(val, then_block, &[]);
+
+                let then_block = self.get_or_make_block(*target)?;
+                let args = self.stack_values();
+                self.builder.ins().brz(val, then_block, &args);
 
                 let block = self.builder.create_block();
                 self.builder.ins().fallthrough(block, &[]);
@@ -138,9 +240,9 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
                 Ok(())
             }
             Instruction::Jump { target } => {
-                let target_block = self.builder.create_block();
-                self.label_to_block.insert(*target, target_block);
-                self.builder.ins().jump(target_block, &[]);
+                let target_block = self.get_or_make_block(*target)?;
+                let args = self.stack_values();
+                self.builder.ins().jump(target_block, &args);
 
                 Ok(())
             }
@@ -158,6 +260,65 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
                 });
                 Ok(())
             }
+            Instruction::LoadName {
+                name,
+                scope: NameScope::Global,
+            } => {
+                let (func_id, sig) = self
+                    .known_funcs
+                    .get(name)
+                    .ok_or(JitCompileError::NotSupported)?;
+                // Record the operand stack depth at load time so `CallFunction` can
+                // confirm this callee is the one its arguments were pushed for,
+                // rather than trusting `func_refs` and `stack` to have stayed in
+                // lockstep on their own.
+                self.func_refs
+                    .push((*func_id, sig.clone(), self.stack.len()));
+                Ok(())
+            }
+            Instruction::CallFunction {
+                typ: CallType::Positional(count),
+            } => {
+                let (func_id, callee_sig, stack_depth_at_load) =
+                    self.func_refs.pop().ok_or(JitCompileError::BadBytecode)?;
+
+                if *count != callee_sig.args.len() {
+                    return Err(JitCompileError::NotSupported);
+                }
+
+                let mut args = Vec::with_capacity(*count);
+                for _ in 0..*count {
+                    args.push(self.stack.pop().ok_or(JitCompileError::BadBytecode)?);
+                }
+                args.reverse();
+
+                // The callee must be consumed by the call that immediately follows
+                // its `LoadName`: if the stack isn't back to the depth it was at
+                // when the callee was loaded, something other than these `count`
+                // arguments came between the two, so `func_refs` can no longer be
+                // trusted to be in sync with `stack`.
+                if self.stack.len() != stack_depth_at_load {
+                    return Err(JitCompileError::NotSupported);
+                }
+
+                for (arg, expected_ty) in args.iter().zip(&callee_sig.args) {
+                    if arg.ty != *expected_ty {
+                        return Err(JitCompileError::NotSupported);
+                    }
+                }
+                let ret_ty = callee_sig
+                    .ret
+                    .clone()
+                    .ok_or(JitCompileError::NotSupported)?;
+
+                let func_ref = self.module.declare_func_in_func(func_id, self.builder.func);
+                let arg_vals: Vec<Value> = args.iter().map(|arg| arg.val).collect();
+                let call = self.builder.ins().call(func_ref, &arg_vals);
+                let val = self.builder.inst_results(call)[0];
+
+                self.stack.push(JitValue { val, ty: ret_ty });
+                Ok(())
+            }
             Instruction::StoreName {
                 name,
                 scope: NameScope::Local,
@@ -188,6 +349,16 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
                 });
                 Ok(())
             }
+            Instruction::LoadConst {
+                value: Constant::Boolean { value },
+            } => {
+                let val = self.builder.ins().iconst(types::I8, *value as i64);
+                self.stack.push(JitValue {
+                    val,
+                    ty: JitType::Bool,
+                });
+                Ok(())
+            }
             Instruction::ReturnValue => {
                 let val = self.stack.pop().ok_or(JitCompileError::BadBytecode)?;
                 if let Some(ref ty) = self.sig.ret {
@@ -209,6 +380,7 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
                 // the rhs is popped off first
                 let b = self.stack.pop().ok_or(JitCompileError::BadBytecode)?;
                 let a = self.stack.pop().ok_or(JitCompileError::BadBytecode)?;
+                let (a, b) = self.coerce_numeric(a, b);
 
                 match (a.ty, b.ty) {
                     (JitType::Int, JitType::Int) => {
@@ -218,14 +390,33 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
                             ComparisonOperator::Less => IntCC::SignedLessThan,
                             ComparisonOperator::LessOrEqual => IntCC::SignedLessThanOrEqual,
                             ComparisonOperator::Greater => IntCC::SignedGreaterThan,
-                            ComparisonOperator::GreaterOrEqual => IntCC::SignedLessThanOrEqual,
+                            ComparisonOperator::GreaterOrEqual => IntCC::SignedGreaterThanOrEqual,
                             _ => return Err(JitCompileError::NotSupported),
                         };
 
                         let val = self.builder.ins().icmp(cond, a.val, b.val);
                         self.stack.push(JitValue {
-                            val,
-                            ty: JitType::Int, // TODO: Boolean
+                            val: self.builder.ins().bint(types::I8, val),
+                            ty: JitType::Bool,
+                        });
+
+                        Ok(())
+                    }
+                    (JitType::Float, JitType::Float) => {
+                        let cond = match op {
+                            ComparisonOperator::Equal => FloatCC::Equal,
+                            ComparisonOperator::NotEqual => FloatCC::NotEqual,
+                            ComparisonOperator::Less => FloatCC::LessThan,
+                            ComparisonOperator::LessOrEqual => FloatCC::LessThanOrEqual,
+                            ComparisonOperator::Greater => FloatCC::GreaterThan,
+                            ComparisonOperator::GreaterOrEqual => FloatCC::GreaterThanOrEqual,
+                            _ => return Err(JitCompileError::NotSupported),
+                        };
+
+                        let val = self.builder.ins().fcmp(cond, a.val, b.val);
+                        self.stack.push(JitValue {
+                            val: self.builder.ins().bint(types::I8, val),
+                            ty: JitType::Bool,
                         });
 
                         Ok(())
@@ -237,6 +428,7 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
                 // the rhs is popped off first
                 let b = self.stack.pop().ok_or(JitCompileError::BadBytecode)?;
                 let a = self.stack.pop().ok_or(JitCompileError::BadBytecode)?;
+                let (a, b) = self.coerce_numeric(a, b);
                 match (a.ty, b.ty) {
                     (JitType::Int, JitType::Int) => match op {
                         BinaryOperator::Add => {
@@ -265,6 +457,132 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
                             });
                             Ok(())
                         }
+                        BinaryOperator::Multiply => {
+                            // Cranelift has no flag-output multiply like it does for
+                            // add/sub, since overflow isn't a single flag for `imul`.
+                            // Instead, widen by hand: the high half of the full
+                            // 128-bit product must equal the sign-extension of the
+                            // low half, or the truncated 64-bit result overflowed.
+                            let out = self.builder.ins().imul(a.val, b.val);
+                            let hi = self.builder.ins().smulhi(a.val, b.val);
+                            let sign = self.builder.ins().sshr_imm(out, 63);
+                            let overflowed = self.builder.ins().icmp(IntCC::NotEqual, hi, sign);
+                            self.builder
+                                .ins()
+                                .trapnz(overflowed, TrapCode::IntegerOverflow);
+                            self.stack.push(JitValue {
+                                val: out,
+                                ty: JitType::Int,
+                            });
+                            Ok(())
+                        }
+                        BinaryOperator::FloorDivide => {
+                            self.builder
+                                .ins()
+                                .trapz(b.val, TrapCode::IntegerDivisionByZero);
+
+                            let q = self.builder.ins().sdiv(a.val, b.val);
+                            let r = self.builder.ins().srem(a.val, b.val);
+
+                            // Python's `//` rounds towards negative infinity, so when the
+                            // remainder is non-zero and its sign differs from the divisor's,
+                            // the truncating `sdiv` result is off by one.
+                            let zero = self.builder.ins().iconst(types::I64, 0);
+                            let r_nonzero = self.builder.ins().icmp(IntCC::NotEqual, r, zero);
+                            let r_xor_b = self.builder.ins().bxor(r, b.val);
+                            let signs_differ =
+                                self.builder
+                                    .ins()
+                                    .icmp(IntCC::SignedLessThan, r_xor_b, zero);
+                            let needs_adjust = self.builder.ins().band(r_nonzero, signs_differ);
+
+                            let q_minus_one = self.builder.ins().iadd_imm(q, -1);
+                            let out = self.builder.ins().select(needs_adjust, q_minus_one, q);
+
+                            self.stack.push(JitValue {
+                                val: out,
+                                ty: JitType::Int,
+                            });
+                            Ok(())
+                        }
+                        BinaryOperator::Modulo => {
+                            self.builder
+                                .ins()
+                                .trapz(b.val, TrapCode::IntegerDivisionByZero);
+
+                            let r = self.builder.ins().srem(a.val, b.val);
+
+                            // Python's `%` takes the sign of the divisor, so when the
+                            // remainder is non-zero and its sign differs from the divisor's,
+                            // add the divisor back in.
+                            let zero = self.builder.ins().iconst(types::I64, 0);
+                            let r_nonzero = self.builder.ins().icmp(IntCC::NotEqual, r, zero);
+                            let r_xor_b = self.builder.ins().bxor(r, b.val);
+                            let signs_differ =
+                                self.builder
+                                    .ins()
+                                    .icmp(IntCC::SignedLessThan, r_xor_b, zero);
+                            let needs_adjust = self.builder.ins().band(r_nonzero, signs_differ);
+
+                            let r_plus_b = self.builder.ins().iadd(r, b.val);
+                            let out = self.builder.ins().select(needs_adjust, r_plus_b, r);
+
+                            self.stack.push(JitValue {
+                                val: out,
+                                ty: JitType::Int,
+                            });
+                            Ok(())
+                        }
+                        BinaryOperator::And => {
+                            self.stack.push(JitValue {
+                                val: self.builder.ins().band(a.val, b.val),
+                                ty: JitType::Int,
+                            });
+                            Ok(())
+                        }
+                        BinaryOperator::Or => {
+                            self.stack.push(JitValue {
+                                val: self.builder.ins().bor(a.val, b.val),
+                                ty: JitType::Int,
+                            });
+                            Ok(())
+                        }
+                        BinaryOperator::Xor => {
+                            self.stack.push(JitValue {
+                                val: self.builder.ins().bxor(a.val, b.val),
+                                ty: JitType::Int,
+                            });
+                            Ok(())
+                        }
+                        BinaryOperator::Lshift => {
+                            // CPython raises ValueError for a negative shift count;
+                            // `ishl` would otherwise just mask it mod 64 and produce
+                            // nonsense, so trap instead.
+                            let zero = self.builder.ins().iconst(types::I64, 0);
+                            let negative_shift =
+                                self.builder.ins().icmp(IntCC::SignedLessThan, b.val, zero);
+                            self.builder
+                                .ins()
+                                .trapnz(negative_shift, TrapCode::User(NEGATIVE_SHIFT_TRAP_CODE));
+                            self.stack.push(JitValue {
+                                val: self.builder.ins().ishl(a.val, b.val),
+                                ty: JitType::Int,
+                            });
+                            Ok(())
+                        }
+                        BinaryOperator::Rshift => {
+                            let zero = self.builder.ins().iconst(types::I64, 0);
+                            let negative_shift =
+                                self.builder.ins().icmp(IntCC::SignedLessThan, b.val, zero);
+                            self.builder
+                                .ins()
+                                .trapnz(negative_shift, TrapCode::User(NEGATIVE_SHIFT_TRAP_CODE));
+                            self.stack.push(JitValue {
+                                val: self.builder.ins().sshr(a.val, b.val),
+                                ty: JitType::Int,
+                            });
+                            Ok(())
+                        }
                         _ => Err(JitCompileError::NotSupported),
                     },
                     (JitType::Float, JitType::Float) => match op {
@@ -305,3 +623,423 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cranelift_jit::{JITBuilder, JITModule};
+    use rustpython_bytecode::bytecode::CodeFlags;
+
+    /// Small builder for hand-assembling the `CodeObject`s these tests compile,
+    /// since the JIT only ever sees already-lowered bytecode.
+    struct CodeBuilder {
+        code: CodeObject,
+    }
+
+    impl CodeBuilder {
+        fn new(arg_count: usize) -> Self {
+            CodeBuilder {
+                code: CodeObject::new(
+                    CodeFlags::default(),
+                    0,
+                    arg_count,
+                    0,
+                    "<test>".to_owned(),
+                    0,
+                    "test".to_owned(),
+                ),
+            }
+        }
+
+        fn emit(&mut self, instr: Instruction) -> &mut Self {
+            self.code.instructions.push(instr);
+            self
+        }
+
+        fn label(&mut self, label: Label) -> &mut Self {
+            self.code
+                .label_map
+                .insert(label, self.code.instructions.len());
+            self
+        }
+
+        fn finish(self) -> CodeObject {
+            self.code
+        }
+    }
+
+    fn load_local(name: &str) -> Instruction {
+        Instruction::LoadName {
+            name: name.to_owned(),
+            scope: NameScope::Local,
+        }
+    }
+
+    fn store_local(name: &str) -> Instruction {
+        Instruction::StoreName {
+            name: name.to_owned(),
+            scope: NameScope::Local,
+        }
+    }
+
+    fn load_const_int(value: i64) -> Instruction {
+        Instruction::LoadConst {
+            value: Constant::Integer {
+                value: value.into(),
+            },
+        }
+    }
+
+    fn new_module() -> JITModule {
+        let mut flag_builder = settings::builder();
+        flag_builder.set("use_colocated_libcalls", "false").unwrap();
+        flag_builder.set("is_pic", "false").unwrap();
+        let isa = cranelift_native::builder()
+            .unwrap()
+            .finish(settings::Flags::new(flag_builder));
+        JITModule::new(JITBuilder::with_isa(
+            isa,
+            cranelift_module::default_libcall_names(),
+        ))
+    }
+
+    fn invoke1(code: &CodeObject, arg_types: &[JitType], arg: i64) -> i64 {
+        let mut module = new_module();
+        let known_funcs = HashMap::new();
+        let (func_id, _sig) = crate::compile_into(
+            &mut module,
+            &known_funcs,
+            code,
+            &["x".to_owned()],
+            arg_types,
+        )
+        .unwrap();
+        module.finalize_definitions();
+        let ptr = module.get_finalized_function(func_id);
+        let func: fn(i64) -> i64 = unsafe { std::mem::transmute(ptr) };
+        func(arg)
+    }
+
+    fn invoke2(code: &CodeObject, arg_types: &[JitType], a: i64, b: i64) -> i64 {
+        let mut module = new_module();
+        let known_funcs = HashMap::new();
+        let (func_id, _sig) = crate::compile_into(
+            &mut module,
+            &known_funcs,
+            code,
+            &["a".to_owned(), "b".to_owned()],
+            arg_types,
+        )
+        .unwrap();
+        module.finalize_definitions();
+        let ptr = module.get_finalized_function(func_id);
+        let func: fn(i64, i64) -> i64 = unsafe { std::mem::transmute(ptr) };
+        func(a, b)
+    }
+
+    fn invoke2_bool(code: &CodeObject, arg_types: &[JitType], a: i64, b: i64) -> bool {
+        let mut module = new_module();
+        let known_funcs = HashMap::new();
+        let (func_id, _sig) = crate::compile_into(
+            &mut module,
+            &known_funcs,
+            code,
+            &["a".to_owned(), "b".to_owned()],
+            arg_types,
+        )
+        .unwrap();
+        module.finalize_definitions();
+        let ptr = module.get_finalized_function(func_id);
+        let func: fn(i64, i64) -> i8 = unsafe { std::mem::transmute(ptr) };
+        func(a, b) != 0
+    }
+
+    fn invoke2_floats_bool(code: &CodeObject, arg_types: &[JitType], a: f64, b: f64) -> bool {
+        let mut module = new_module();
+        let known_funcs = HashMap::new();
+        let (func_id, _sig) = crate::compile_into(
+            &mut module,
+            &known_funcs,
+            code,
+            &["a".to_owned(), "b".to_owned()],
+            arg_types,
+        )
+        .unwrap();
+        module.finalize_definitions();
+        let ptr = module.get_finalized_function(func_id);
+        let func: fn(f64, f64) -> i8 = unsafe { std::mem::transmute(ptr) };
+        func(a, b) != 0
+    }
+
+    fn compare_code(op: ComparisonOperator) -> CodeObject {
+        let mut b = CodeBuilder::new(2);
+        b.emit(load_local("a"))
+            .emit(load_local("b"))
+            .emit(Instruction::CompareOperation { op })
+            .emit(Instruction::ReturnValue);
+        b.finish()
+    }
+
+    #[test]
+    fn if_else_merges_local_variable() {
+        // def f(x):
+        //     if x: y = 1
+        //     else: y = 2
+        //     return y
+        let else_label = Label(0);
+        let end_label = Label(1);
+
+        let mut b = CodeBuilder::new(1);
+        b.emit(load_local("x"))
+            .emit(Instruction::JumpIfFalse { target: else_label })
+            .emit(load_const_int(1))
+            .emit(store_local("y"))
+            .emit(Instruction::Jump { target: end_label })
+            .label(else_label)
+            .emit(load_const_int(2))
+            .emit(store_local("y"))
+            .label(end_label)
+            .emit(load_local("y"))
+            .emit(Instruction::ReturnValue);
+        let code = b.finish();
+
+        assert_eq!(invoke1(&code, &[JitType::Int], 1), 1);
+        assert_eq!(invoke1(&code, &[JitType::Int], 0), 2);
+    }
+
+    #[test]
+    fn while_loop_carries_accumulator_across_merge() {
+        // def f(x):
+        //     total = 0
+        //     i = 0
+        //     while i < x:
+        //         total = total + i
+        //         i = i + 1
+        //     return total
+        let loop_start = Label(0);
+        let loop_end = Label(1);
+
+        let mut b = CodeBuilder::new(1);
+        b.emit(load_const_int(0))
+            .emit(store_local("total"))
+            .emit(load_const_int(0))
+            .emit(store_local("i"))
+            .label(loop_start)
+            .emit(load_local("i"))
+            .emit(load_local("x"))
+            .emit(Instruction::CompareOperation {
+                op: ComparisonOperator::Less,
+            })
+            .emit(Instruction::JumpIfFalse { target: loop_end })
+            .emit(load_local("total"))
+            .emit(load_local("i"))
+            .emit(Instruction::BinaryOperation {
+                op: BinaryOperator::Add,
+                inplace: false,
+            })
+            .emit(store_local("total"))
+            .emit(load_local("i"))
+            .emit(load_const_int(1))
+            .emit(Instruction::BinaryOperation {
+                op: BinaryOperator::Add,
+                inplace: false,
+            })
+            .emit(store_local("i"))
+            .emit(Instruction::Jump { target: loop_start })
+            .label(loop_end)
+            .emit(load_local("total"))
+            .emit(Instruction::ReturnValue);
+        let code = b.finish();
+
+        assert_eq!(invoke1(&code, &[JitType::Int], 5), 10); // 0+1+2+3+4
+        assert_eq!(invoke1(&code, &[JitType::Int], 0), 0);
+    }
+
+    #[test]
+    fn floor_div_and_modulo_match_python_semantics_for_negative_operands() {
+        let mut fd = CodeBuilder::new(2);
+        fd.emit(load_local("a"))
+            .emit(load_local("b"))
+            .emit(Instruction::BinaryOperation {
+                op: BinaryOperator::FloorDivide,
+                inplace: false,
+            })
+            .emit(Instruction::ReturnValue);
+        let floordiv = fd.finish();
+
+        let mut md = CodeBuilder::new(2);
+        md.emit(load_local("a"))
+            .emit(load_local("b"))
+            .emit(Instruction::BinaryOperation {
+                op: BinaryOperator::Modulo,
+                inplace: false,
+            })
+            .emit(Instruction::ReturnValue);
+        let modulo = md.finish();
+
+        let arg_types = [JitType::Int, JitType::Int];
+
+        // -7 // 2 == -4, -7 % 2 == 1 (Python rounds towards negative infinity
+        // and the remainder takes the divisor's sign).
+        assert_eq!(invoke2(&floordiv, &arg_types, -7, 2), -4);
+        assert_eq!(invoke2(&modulo, &arg_types, -7, 2), 1);
+        // 7 // -2 == -4, 7 % -2 == -1
+        assert_eq!(invoke2(&floordiv, &arg_types, 7, -2), -4);
+        assert_eq!(invoke2(&modulo, &arg_types, 7, -2), -1);
+    }
+
+    #[test]
+    fn comparison_operators_match_python_semantics_for_ints() {
+        use ComparisonOperator::*;
+
+        let arg_types = [JitType::Int, JitType::Int];
+        let cases = [
+            (Equal, 3, 3, true),
+            (Equal, 3, 4, false),
+            (NotEqual, 3, 4, true),
+            (NotEqual, 3, 3, false),
+            (Less, 3, 4, true),
+            (Less, 4, 3, false),
+            (LessOrEqual, 3, 3, true),
+            (LessOrEqual, 4, 3, false),
+            (Greater, 4, 3, true),
+            (Greater, 3, 4, false),
+            (GreaterOrEqual, 3, 3, true),
+            (GreaterOrEqual, 3, 4, false),
+        ];
+
+        for (op, a, b, expected) in cases.iter().cloned() {
+            let code = compare_code(op.clone());
+            assert_eq!(
+                invoke2_bool(&code, &arg_types, a, b),
+                expected,
+                "{:?}({}, {})",
+                op,
+                a,
+                b
+            );
+        }
+    }
+
+    #[test]
+    fn comparison_operators_match_python_semantics_for_floats() {
+        use ComparisonOperator::*;
+
+        let arg_types = [JitType::Float, JitType::Float];
+        let cases = [
+            (Equal, 3.0, 3.0, true),
+            (Equal, 3.0, 4.0, false),
+            (NotEqual, 3.0, 4.0, true),
+            (NotEqual, 3.0, 3.0, false),
+            (Less, 3.0, 4.0, true),
+            (Less, 4.0, 3.0, false),
+            (LessOrEqual, 3.0, 3.0, true),
+            (LessOrEqual, 4.0, 3.0, false),
+            (Greater, 4.0, 3.0, true),
+            (Greater, 3.0, 4.0, false),
+            (GreaterOrEqual, 3.0, 3.0, true),
+            (GreaterOrEqual, 3.0, 4.0, false),
+        ];
+
+        for (op, a, b, expected) in cases.iter().cloned() {
+            let code = compare_code(op.clone());
+            assert_eq!(
+                invoke2_floats_bool(&code, &arg_types, a, b),
+                expected,
+                "{:?}({}, {})",
+                op,
+                a,
+                b
+            );
+        }
+    }
+
+    #[test]
+    fn if_else_merges_value_left_on_operand_stack() {
+        // def f(x):
+        //     return 1 if x else 2
+        //
+        // Unlike `if_else_merges_local_variable` above, the merged value here
+        // never goes through a named local - it's carried purely as a live
+        // operand-stack value across the branch, which is what actually
+        // exercises `BlockWithStack`'s block-param threading rather than
+        // `StoreName`/`LoadName`.
+        let else_label = Label(0);
+        let end_label = Label(1);
+
+        let mut b = CodeBuilder::new(1);
+        b.emit(load_local("x"))
+            .emit(Instruction::JumpIfFalse { target: else_label })
+            .emit(load_const_int(1))
+            .emit(Instruction::Jump { target: end_label })
+            .label(else_label)
+            .emit(load_const_int(2))
+            .label(end_label)
+            .emit(Instruction::ReturnValue);
+        let code = b.finish();
+
+        assert_eq!(invoke1(&code, &[JitType::Int], 1), 1);
+        assert_eq!(invoke1(&code, &[JitType::Int], 0), 2);
+    }
+
+    #[test]
+    fn calling_one_jit_function_from_another() {
+        // def square(x): return x * x
+        // def caller(x): return square(x) + 1
+        let mut sq = CodeBuilder::new(1);
+        sq.emit(load_local("x"))
+            .emit(load_local("x"))
+            .emit(Instruction::BinaryOperation {
+                op: BinaryOperator::Multiply,
+                inplace: false,
+            })
+            .emit(Instruction::ReturnValue);
+        let square_code = sq.finish();
+
+        let mut module = new_module();
+        let mut known_funcs = HashMap::new();
+
+        let (square_id, square_sig) = crate::compile_into(
+            &mut module,
+            &known_funcs,
+            &square_code,
+            &["x".to_owned()],
+            &[JitType::Int],
+        )
+        .unwrap();
+        known_funcs.insert("square".to_owned(), (square_id, square_sig));
+
+        let mut caller = CodeBuilder::new(1);
+        caller
+            .emit(Instruction::LoadName {
+                name: "square".to_owned(),
+                scope: NameScope::Global,
+            })
+            .emit(load_local("x"))
+            .emit(Instruction::CallFunction {
+                typ: CallType::Positional(1),
+            })
+            .emit(load_const_int(1))
+            .emit(Instruction::BinaryOperation {
+                op: BinaryOperator::Add,
+                inplace: false,
+            })
+            .emit(Instruction::ReturnValue);
+        let caller_code = caller.finish();
+
+        let (caller_id, _caller_sig) = crate::compile_into(
+            &mut module,
+            &known_funcs,
+            &caller_code,
+            &["x".to_owned()],
+            &[JitType::Int],
+        )
+        .unwrap();
+        module.finalize_definitions();
+
+        let ptr = module.get_finalized_function(caller_id);
+        let func: fn(i64) -> i64 = unsafe { std::mem::transmute(ptr) };
+        assert_eq!(func(5), 26); // 5*5 + 1
+        assert_eq!(func(0), 1);
+    }
+}
@@ -0,0 +1,178 @@
+#![cfg_attr(target_arch = "wasm32", allow(dead_code, unused_imports))]
+
+use cranelift::prelude::*;
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{FuncId, Linkage, Module, ModuleError};
+use rustpython_bytecode::bytecode::CodeObject;
+use std::collections::HashMap;
+use std::fmt;
+
+mod instructions;
+
+#[derive(Debug)]
+pub enum JitCompileError {
+    NotSupported,
+    BadBytecode,
+    CraneliftError(ModuleError),
+}
+
+impl From<ModuleError> for JitCompileError {
+    fn from(err: ModuleError) -> Self {
+        JitCompileError::CraneliftError(err)
+    }
+}
+
+impl fmt::Display for JitCompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JitCompileError::NotSupported => write!(f, "Cannot compile the given bytecode"),
+            JitCompileError::BadBytecode => write!(f, "Bad bytecode"),
+            JitCompileError::CraneliftError(err) => write!(f, "Cranelift error: {}", err),
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum JitType {
+    Int,
+    Float,
+    Bool,
+}
+
+impl JitType {
+    fn to_cranelift(&self) -> types::Type {
+        match self {
+            JitType::Int => types::I64,
+            JitType::Float => types::F64,
+            JitType::Bool => types::I8,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct JitSig {
+    args: Vec<JitType>,
+    ret: Option<JitType>,
+}
+
+impl JitSig {
+    fn to_cranelift(&self) -> Signature {
+        let mut sig = Signature::new(cranelift_module::default_call_conv());
+        sig.params = self
+            .args
+            .iter()
+            .map(|arg| AbiParam::new(arg.to_cranelift()))
+            .collect();
+        if let Some(ref ret) = self.ret {
+            sig.returns.push(AbiParam::new(ret.to_cranelift()));
+        }
+        sig
+    }
+}
+
+/// Compile a single function with no callees, the way a one-off script or a
+/// `timeit`-style benchmark would. This is a thin wrapper around
+/// [`compile_into`] for callers that don't need to build up a call graph of
+/// several JIT-compiled functions sharing one `JITModule`: it builds its own
+/// module, finalizes it, and hands back something invocable directly.
+pub fn compile(
+    bytecode: &CodeObject,
+    arg_names: &[String],
+    arg_types: &[JitType],
+) -> Result<CompiledCode, JitCompileError> {
+    let mut module = new_module();
+    let known_funcs = HashMap::new();
+    let (func_id, sig) = compile_into(&mut module, &known_funcs, bytecode, arg_names, arg_types)?;
+    module.finalize_definitions();
+    Ok(CompiledCode {
+        module,
+        func_id,
+        sig,
+    })
+}
+
+fn new_module() -> JITModule {
+    let mut flag_builder = settings::builder();
+    flag_builder.set("use_colocated_libcalls", "false").unwrap();
+    flag_builder.set("is_pic", "false").unwrap();
+    let isa = cranelift_native::builder()
+        .unwrap()
+        .finish(settings::Flags::new(flag_builder));
+    let builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
+    JITModule::new(builder)
+}
+
+/// A JIT-compiled function, ready to be called once its owning `JITModule`
+/// has finalized its definitions.
+pub struct CompiledCode {
+    module: JITModule,
+    func_id: FuncId,
+    #[allow(dead_code)]
+    sig: JitSig,
+}
+
+impl CompiledCode {
+    /// Calls the function with no arguments and discards its return value.
+    ///
+    /// # Safety
+    /// The `CodeObject` must have been compiled with an empty `arg_types`.
+    pub unsafe fn invoke(&self) {
+        let code_ptr = self.module.get_finalized_function(self.func_id);
+        let func = std::mem::transmute::<_, fn()>(code_ptr);
+        func()
+    }
+}
+
+/// Compile `bytecode` into `module`, returning the `FuncId` it was declared
+/// under together with its inferred signature.
+///
+/// `known_funcs` maps the name a Python-level global resolves to onto a
+/// function already declared in `module`, so that the body being compiled
+/// here can call it. Compiling a call graph of helper functions is just a
+/// matter of calling this once per function, in dependency order, growing
+/// `known_funcs` with each result before compiling the next, and finalizing
+/// the module yourself once every function in the graph has been compiled.
+pub fn compile_into(
+    module: &mut JITModule,
+    known_funcs: &HashMap<String, (FuncId, JitSig)>,
+    bytecode: &CodeObject,
+    arg_names: &[String],
+    arg_types: &[JitType],
+) -> Result<(FuncId, JitSig), JitCompileError> {
+    let mut ctx = module.make_context();
+    ctx.func.signature = JitSig {
+        args: arg_types.to_vec(),
+        ret: None,
+    }
+    .to_cranelift();
+
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+
+    let entry_block = builder.create_block();
+    builder.append_block_params_for_function_params(entry_block);
+    builder.switch_to_block(entry_block);
+    builder.seal_block(entry_block);
+
+    let mut compiler = instructions::FunctionCompiler::new(
+        &mut builder,
+        module,
+        known_funcs,
+        arg_names,
+        arg_types,
+        entry_block,
+    );
+    compiler.compile(bytecode)?;
+    let sig = compiler.sig.clone();
+
+    builder.seal_all_blocks();
+    builder.finalize();
+
+    let func_id =
+        module.declare_function(&bytecode.obj_name, Linkage::Export, &ctx.func.signature)?;
+    module.define_function(func_id, &mut ctx)?;
+    module.clear_context(&mut ctx);
+
+    Ok((func_id, sig))
+}